@@ -1,11 +1,204 @@
-use chrono::Local;
+use backtrace::Backtrace;
+use chrono::{DateTime, Duration, Local};
 use colored::{ColoredString, Colorize};
 use log::{Level, Log, Metadata, Record, SetLoggerError};
-use std::sync::{Mutex, OnceLock};
+use regex::Regex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, RwLock};
+
+/// A single record retained by `Logger`'s in-memory history.
+#[derive(Clone)]
+pub struct LogRecord {
+    pub timestamp: DateTime<Local>,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// How the in-memory history is bounded.
+#[derive(Clone, Copy)]
+enum HistoryLimit {
+    Count(usize),
+    Age(Duration),
+}
+
+/// A query over the in-memory history; every field is an optional narrowing.
+#[derive(Default)]
+pub struct Filter {
+    pub min_level: Option<Level>,
+    pub target: Option<String>,
+    pub message: Option<Regex>,
+    pub not_before: Option<DateTime<Local>>,
+    pub limit: Option<usize>,
+}
+
+/// A sink writing plain (ANSI-free) lines to a file, gated by its own level.
+struct FileSink {
+    file: File,
+    level: Level,
+    dedup: Option<Mutex<HashSet<String>>>,
+}
+
+impl FileSink {
+    fn open(path: impl AsRef<Path>, level: Level, dedup: bool) -> io::Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let dedup = dedup.then(|| Mutex::new(HashSet::new()));
+        Ok(FileSink {
+            file,
+            level,
+            dedup,
+        })
+    }
+}
+
+/// The body a duplicate is recognized by: target + rendered args, ignoring
+/// the timestamp so the same message repeated a moment later still matches.
+fn dedup_key(record: &Record) -> String {
+    format!("{}\u{0}{}", record.target(), record.args())
+}
+
+/// A single panic backtrace frame, either symbolicated or raw.
+enum PanicFrame {
+    Resolved {
+        symbol: String,
+        location: Option<String>,
+    },
+    Raw(String),
+}
+
+impl PanicFrame {
+    fn render(&self, index: usize) -> String {
+        match self {
+            PanicFrame::Resolved {
+                symbol,
+                location: Some(location),
+            } => format!("{}: {}\n\t\tat {}", index, symbol, location),
+            PanicFrame::Resolved {
+                symbol,
+                location: None,
+            } => format!("{}: {}", index, symbol),
+            PanicFrame::Raw(raw) => format!("{}: {}", index, raw),
+        }
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            PanicFrame::Resolved { symbol, location } => serde_json::json!({
+                "symbol": symbol,
+                "location": location,
+            }),
+            PanicFrame::Raw(raw) => serde_json::json!({ "raw": raw }),
+        }
+    }
+}
+
+// Frames belonging to backtrace capture and our own panic hook, always at
+// the top, that a reader doesn't want counted as frame 0 in their own trace.
+// Matched with `contains`, not `starts_with`: the backtrace crate renders
+// symbols with a per-crate disambiguator hash baked in (e.g.
+// `std[e28293b1aa0f68bd]::panicking::panic_fmt`), so a prefix check against
+// the bare path never matches.
+const NOISE_SYMBOL_SUBSTRINGS: &[&str] = &[
+    "Backtrace::new",
+    "::capture_panic_frames",
+    "::init::{{closure}}",
+    "::init::{closure#0}",
+    "__rust_end_short_backtrace",
+    "::panicking::",
+    "::panic::",
+    "rust_begin_unwind",
+];
+
+/// Captures the current backtrace as structured, numbered frames. Symbols
+/// are resolved eagerly by `Backtrace::new()`, the same way under
+/// `RUST_BACKTRACE=1` and `=full`; a frame with no symbol information is
+/// kept as its raw instruction address.
+fn capture_panic_frames() -> Vec<PanicFrame> {
+    let backtrace = Backtrace::new();
+
+    let mut frames = Vec::new();
+    for frame in backtrace.frames() {
+        let symbols = frame.symbols();
+        if symbols.is_empty() {
+            // No debug info for this frame at all; keep its address so the
+            // frame count/numbering still lines up with the real stack.
+            frames.push(PanicFrame::Raw(format!("{:?}", frame.ip())));
+            continue;
+        }
+        for symbol in symbols {
+            let name = symbol
+                .name()
+                .map(|n| n.to_string())
+                .unwrap_or_else(|| "<unknown>".to_string());
+            let location = match (symbol.filename(), symbol.lineno()) {
+                (Some(file), Some(line)) => Some(format!("{}:{}", file.display(), line)),
+                _ => None,
+            };
+            frames.push(PanicFrame::Resolved {
+                symbol: name,
+                location,
+            });
+        }
+    }
+
+    // Drop the leading run of noise frames (our own hook and the std/core
+    // panic machinery, through `core::panicking::panic_fmt` below
+    // `rust_begin_unwind`), so frame 0 is the code that actually panicked.
+    frames
+        .into_iter()
+        .skip_while(|frame| match frame {
+            PanicFrame::Resolved { symbol, .. } => NOISE_SYMBOL_SUBSTRINGS
+                .iter()
+                .any(|needle| symbol.contains(needle)),
+            PanicFrame::Raw(_) => false,
+        })
+        .collect()
+}
+
+/// Output format for both the console and file sinks.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `"{time} {level}: {target} - {args}"`, colored on the console.
+    Text,
+    /// A single-line JSON object per record, for log aggregators.
+    Json,
+}
+
+/// Strips ANSI color escape sequences so colored console output can be
+/// reused verbatim for sinks that shouldn't carry terminal escapes (files).
+fn strip_ansi_codes(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' && chars.peek() == Some(&'[') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            output.push(c);
+        }
+    }
+    output
+}
 
 pub struct Logger {
     pub log_level: Mutex<Level>,
     pub crate_levels: Mutex<Vec<(String, Level)>>,
+    file_sink: Mutex<Option<FileSink>>,
+    format: Mutex<Format>,
+    history: Mutex<VecDeque<LogRecord>>,
+    history_limit: Mutex<Option<HistoryLimit>>,
+    // Bumped by set_level/set_crate_log; invalidates interest_cache entries.
+    epoch: AtomicU64,
+    interest_cache: RwLock<HashMap<String, (u64, Level)>>,
+    console_dedup: Mutex<Option<HashSet<String>>>,
 }
 
 static LOGGER: OnceLock<Logger> = OnceLock::new();
@@ -15,11 +208,159 @@ impl Logger {
         Logger {
             log_level: Mutex::new(level),
             crate_levels: Mutex::new(Vec::new()),
+            file_sink: Mutex::new(None),
+            format: Mutex::new(Format::Text),
+            history: Mutex::new(VecDeque::new()),
+            history_limit: Mutex::new(None),
+            epoch: AtomicU64::new(0),
+            interest_cache: RwLock::new(HashMap::new()),
+            console_dedup: Mutex::new(None),
+        }
+    }
+
+    /// Suppresses repeated console records whose target+args were already
+    /// seen this session. Call `reset_dedup` to forget them.
+    pub fn with_console_dedup(self) -> Logger {
+        *self.console_dedup.lock().unwrap() = Some(HashSet::new());
+        self
+    }
+
+    /// Clears the dedup sets, for long-running processes that want to
+    /// start collapsing repeats fresh (e.g. after a new work cycle begins).
+    pub fn reset_dedup(&self) {
+        if let Some(seen) = self.console_dedup.lock().unwrap().as_mut() {
+            seen.clear();
+        }
+        if let Some(sink) = self.file_sink.lock().unwrap().as_ref() {
+            if let Some(seen) = &sink.dedup {
+                seen.lock().unwrap().clear();
+            }
+        }
+    }
+
+    fn is_duplicate_console(&self, key: &str) -> bool {
+        match self.console_dedup.lock().unwrap().as_mut() {
+            Some(seen) => !seen.insert(key.to_string()),
+            None => false,
+        }
+    }
+
+    fn is_duplicate_file(&self, key: &str) -> bool {
+        let file_sink = self.file_sink.lock().unwrap();
+        match file_sink.as_ref().and_then(|sink| sink.dedup.as_ref()) {
+            Some(seen) => !seen.lock().unwrap().insert(key.to_string()),
+            None => false,
+        }
+    }
+
+    /// Retains only the last `capacity` records for `query()`.
+    pub fn with_history_capacity(self, capacity: usize) -> Logger {
+        *self.history_limit.lock().unwrap() = Some(HistoryLimit::Count(capacity));
+        self
+    }
+
+    /// Retains only records newer than `retention` for `query()`.
+    pub fn with_history_retention(self, retention: Duration) -> Logger {
+        *self.history_limit.lock().unwrap() = Some(HistoryLimit::Age(retention));
+        self
+    }
+
+    /// Searches the in-memory history, most permissive filter fields skipped.
+    pub fn query(&self, filter: Filter) -> Vec<LogRecord> {
+        // Age eviction only runs on push, so re-apply it for a quiet period.
+        let cutoff = match *self.history_limit.lock().unwrap() {
+            Some(HistoryLimit::Age(retention)) => Some(Local::now() - retention),
+            _ => None,
+        };
+        let history = self.history.lock().unwrap();
+        let mut results: Vec<LogRecord> = history
+            .iter()
+            .filter(|r| filter.min_level.map_or(true, |min| r.level <= min))
+            .filter(|r| {
+                filter
+                    .target
+                    .as_deref()
+                    .map_or(true, |t| r.target.contains(t))
+            })
+            .filter(|r| {
+                filter
+                    .message
+                    .as_ref()
+                    .map_or(true, |re| re.is_match(&r.message))
+            })
+            .filter(|r| filter.not_before.map_or(true, |ts| r.timestamp >= ts))
+            .filter(|r| cutoff.map_or(true, |c| r.timestamp >= c))
+            .cloned()
+            .collect();
+        if let Some(limit) = filter.limit {
+            // `history` (and thus `results`) is oldest-to-newest, so the
+            // most recent matches are the tail, not the head.
+            let excess = results.len().saturating_sub(limit);
+            results.drain(0..excess);
+        }
+        results
+    }
+
+    fn push_history(&self, record: &Record) {
+        let Some(limit) = *self.history_limit.lock().unwrap() else {
+            return;
+        };
+        let mut history = self.history.lock().unwrap();
+        history.push_back(LogRecord {
+            timestamp: Local::now(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        });
+        match limit {
+            HistoryLimit::Count(capacity) => {
+                while history.len() > capacity {
+                    history.pop_front();
+                }
+            }
+            HistoryLimit::Age(retention) => {
+                let cutoff = Local::now() - retention;
+                while history.front().is_some_and(|r| r.timestamp < cutoff) {
+                    history.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Attaches a file sink with its own level filter, independent of the
+    /// console's. Consumes and returns `self` so it can be chained onto
+    /// `new`; on failure to open `path`, `self` is handed back unchanged
+    /// alongside the error so the caller doesn't lose it.
+    pub fn with_file_sink(
+        self,
+        path: impl AsRef<Path>,
+        level: Level,
+        dedup: bool,
+    ) -> Result<Logger, (Logger, io::Error)> {
+        match FileSink::open(path, level, dedup) {
+            Ok(sink) => {
+                *self.file_sink.lock().unwrap() = Some(sink);
+                Ok(self)
+            }
+            Err(e) => Err((self, e)),
         }
     }
 
     pub fn set_level(&self, level: Level) {
         *self.log_level.lock().unwrap() = level;
+        self.epoch.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn set_crate_log(&self, target: &str, level: Level) {
+        self.crate_levels
+            .lock()
+            .unwrap()
+            .push((target.to_string(), level));
+        self.epoch.fetch_add(1, Ordering::Release);
+    }
+
+    pub fn set_format(&self, format: Format) {
+        *self.format.lock().unwrap() = format;
     }
 
     pub fn colorize(&self, level: Level) -> ColoredString {
@@ -31,46 +372,226 @@ impl Logger {
             Level::Trace => level.as_str().purple(),
         }
     }
-}
 
-impl Log for Logger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        let log_level = *self.log_level.lock().unwrap();
+    // The level a target resolves to: the longest registered crate/module
+    // prefix it matches at a `::` boundary, or the global level otherwise.
+    fn resolve_console_level(&self, target: &str) -> Level {
         let crate_levels = self.crate_levels.lock().unwrap();
-        let crate_name = metadata.target().split("::").next().unwrap();
-        // FIXME: depending on order added crate::module may inherit the level of crate
+        let mut best: Option<(&str, Level)> = None;
         for (name, level) in crate_levels.iter() {
-            if crate_name == name {
-                return metadata.level() <= *level;
+            let matches =
+                target == name.as_str() || target.starts_with(&format!("{}::", name));
+            if matches && best.map_or(true, |(best_name, _)| name.len() > best_name.len()) {
+                best = Some((name, *level));
+            }
+        }
+
+        match best {
+            Some((_, level)) => level,
+            None => *self.log_level.lock().unwrap(),
+        }
+    }
+
+    fn console_enabled(&self, metadata: &Metadata) -> bool {
+        let target = metadata.target();
+        let epoch = self.epoch.load(Ordering::Acquire);
+
+        if let Some(&(stamp, level)) = self.interest_cache.read().unwrap().get(target) {
+            if stamp == epoch {
+                return metadata.level() <= level;
+            }
+        }
+
+        let level = self.resolve_console_level(target);
+        self.interest_cache
+            .write()
+            .unwrap()
+            .insert(target.to_string(), (epoch, level));
+        metadata.level() <= level
+    }
+
+    fn file_enabled(&self, metadata: &Metadata) -> bool {
+        self.file_sink
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|sink| metadata.level() <= sink.level)
+    }
+
+    fn json_line(&self, record: &Record) -> String {
+        let mut obj = serde_json::json!({
+            "timestamp": Local::now().to_rfc3339(),
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        });
+        if let Some(file) = record.file() {
+            obj["file"] = serde_json::Value::from(file);
+        }
+        if let Some(line) = record.line() {
+            obj["line"] = serde_json::Value::from(line);
+        }
+        obj.to_string()
+    }
+
+    /// Logs a panic through the same console/file sinks as any other
+    /// record, but structurally (location, payload, frames) rather than
+    /// as one pre-formatted multiline string.
+    fn emit_panic(&self, location: &str, payload: &str, frames: &[PanicFrame]) {
+        let metadata = Metadata::builder().level(Level::Error).target("panic").build();
+        let console_enabled = self.console_enabled(&metadata);
+        let file_enabled = self.file_enabled(&metadata);
+        if !console_enabled && !file_enabled {
+            return;
+        }
+
+        let format = *self.format.lock().unwrap();
+        let (console_line, file_line) = match format {
+            Format::Json => {
+                let line = serde_json::json!({
+                    "timestamp": Local::now().to_rfc3339(),
+                    "level": "ERROR",
+                    "target": "panic",
+                    "location": location,
+                    "payload": payload,
+                    "frames": frames.iter().map(PanicFrame::to_json).collect::<Vec<_>>(),
+                })
+                .to_string();
+                (line.clone(), line)
+            }
+            Format::Text => {
+                let mut line = format!(
+                    "{} {}: panic at {} - {}",
+                    Local::now()
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string()
+                        .bright_black(),
+                    self.colorize(Level::Error),
+                    location.black(),
+                    payload.bright_red(),
+                );
+                for (i, frame) in frames.iter().enumerate() {
+                    line.push_str(&format!("\n\t{}", frame.render(i)));
+                }
+                let stripped = strip_ansi_codes(&line);
+                (line, stripped)
             }
+        };
+
+        if console_enabled {
+            eprintln!("{}", console_line);
         }
 
-        return metadata.level() <= log_level;
+        if file_enabled {
+            let mut file_sink = self.file_sink.lock().unwrap();
+            if let Some(sink) = file_sink.as_mut() {
+                let _ = writeln!(sink.file, "{}", file_line);
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.console_enabled(metadata) || self.file_enabled(metadata)
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            eprintln!(
-                "{} {}: {} - {}",
-                Local::now()
-                    .format("%Y-%m-%d %H:%M:%S")
-                    .to_string()
-                    .bright_black(),
-                self.colorize(record.level()),
-                record.target().bright_blue(),
-                record.args()
-            );
+        // Recorded regardless of sink enablement: the in-memory history is
+        // an independent diagnostic buffer, not just console/file overflow.
+        self.push_history(record);
+
+        let console_enabled = self.console_enabled(record.metadata());
+        let file_enabled = self.file_enabled(record.metadata());
+        if !console_enabled && !file_enabled {
+            return;
+        }
+
+        let key = dedup_key(record);
+        let console_enabled = console_enabled && !self.is_duplicate_console(&key);
+        let file_enabled = file_enabled && !self.is_duplicate_file(&key);
+        if !console_enabled && !file_enabled {
+            return;
+        }
+
+        let format = *self.format.lock().unwrap();
+        let (console_line, file_line) = match format {
+            Format::Json => {
+                let line = self.json_line(record);
+                (line.clone(), line)
+            }
+            Format::Text => {
+                let line = format!(
+                    "{} {}: {} - {}",
+                    Local::now()
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string()
+                        .bright_black(),
+                    self.colorize(record.level()),
+                    record.target().bright_blue(),
+                    record.args()
+                );
+                let stripped = strip_ansi_codes(&line);
+                (line, stripped)
+            }
+        };
+
+        if console_enabled {
+            eprintln!("{}", console_line);
+        }
+
+        if file_enabled {
+            let mut file_sink = self.file_sink.lock().unwrap();
+            if let Some(sink) = file_sink.as_mut() {
+                let _ = writeln!(sink.file, "{}", file_line);
+            }
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Some(sink) = self.file_sink.lock().unwrap().as_mut() {
+            let _ = sink.file.flush();
+        }
+    }
 }
 
 pub fn init() -> Result<(), SetLoggerError> {
     let logger = LOGGER.get_or_init(|| {
         let env_level = std::env::var("RUST_LOG").unwrap_or("info".to_string());
         let level = env_level.parse().unwrap_or(Level::Info);
-        return Logger::new(level);
+        let mut logger = Logger::new(level);
+
+        if let Ok(path) = std::env::var("NYAAAN_LOG_FILE") {
+            let file_level = std::env::var("NYAAAN_LOG_FILE_LEVEL")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or(Level::Trace);
+            let file_dedup = std::env::var("NYAAAN_LOG_FILE_DEDUP").as_deref() == Ok("1");
+            logger = match logger.with_file_sink(&path, file_level, file_dedup) {
+                Ok(with_sink) => with_sink,
+                Err((without_sink, e)) => {
+                    eprintln!("failed to open NYAAAN_LOG_FILE {}: {}", path, e);
+                    without_sink
+                }
+            };
+        }
+
+        if std::env::var("NYAAAN_LOG_FORMAT").as_deref() == Ok("json") {
+            logger.set_format(Format::Json);
+        }
+
+        if std::env::var("NYAAAN_LOG_DEDUP").as_deref() == Ok("1") {
+            logger = logger.with_console_dedup();
+        }
+
+        if let Some(capacity) = std::env::var("NYAAAN_LOG_HISTORY")
+            .ok()
+            .and_then(|s| s.parse().ok())
+        {
+            logger = logger.with_history_capacity(capacity);
+        }
+
+        return logger;
     });
 
     std::panic::set_hook(Box::new(move |info| {
@@ -80,43 +601,20 @@ pub fn init() -> Result<(), SetLoggerError> {
         let payload = info
             .payload()
             .downcast_ref::<String>()
-            .map(|s| s.clone())
+            .cloned()
             .unwrap_or_else(|| {
                 info.payload()
                     .downcast_ref::<&str>()
                     .unwrap_or(&"Unknown Payload")
                     .to_string()
             });
-        // This treats newlines as a pseudo "stack trace" for the panic
-        let payload = payload
-            .lines()
-            .enumerate()
-            .map(|(i, line)| match (i, line.trim().is_empty()) {
-                (_, true) => String::new(),
-                (0, _) => format!("{}", line),
-                _ => format!("\t\t||  {}", line),
-            })
-            .filter(|line| !line.is_empty())
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        let trace = match std::env::var("RUST_BACKTRACE") {
-            Ok(_) => std::backtrace::Backtrace::capture().to_string(),
-            Err(_) => {
-                "  Run with RUST_BACKTRACE=1 environment variable to display backtrace".to_string()
-            }
+
+        let frames = match std::env::var("RUST_BACKTRACE") {
+            Ok(_) => capture_panic_frames(),
+            Err(_) => Vec::new(),
         };
-        let trace = trace
-            .lines()
-            .map(|line| format!("\t\t|{}", line))
-            .collect::<Vec<_>>()
-            .join("\n");
-        log::error!(
-            "Panic occurred at: {}\n\t\t-----------------> {}\n{}",
-            location.black(),
-            payload.bright_red(),
-            trace
-        );
+
+        logger.emit_panic(&location, &payload, &frames);
     }));
 
     return log::set_logger(logger).map(|()| log::set_max_level(log::LevelFilter::Trace));
@@ -126,14 +624,16 @@ pub fn set_level(level: Level) {
     LOGGER.get().unwrap().set_level(level);
 }
 
+pub fn set_format(format: Format) {
+    LOGGER.get().unwrap().set_format(format);
+}
+
 pub fn set_crate_log(target: &str, level: Level) {
-    LOGGER
-        .get()
-        .unwrap()
-        .crate_levels
-        .lock()
-        .unwrap()
-        .push((target.to_string(), level));
+    LOGGER.get().unwrap().set_crate_log(target, level);
+}
+
+pub fn reset_dedup() {
+    LOGGER.get().unwrap().reset_dedup();
 }
 
 pub fn get_raw_logger() -> &'static Logger {